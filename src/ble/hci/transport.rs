@@ -0,0 +1,470 @@
+//! Framed HCI transport: serializes LE controller commands, frames them for a 3-wire UART/serial
+//! controller, and correlates Command Complete/Command Status events back to the request that
+//! triggered them.
+use crate::ble::hci::{HCIConversionError, LEControllerOpcode, Opcode};
+use alloc::collections::btree_map;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use futures::channel::{mpsc, oneshot};
+use futures::lock::Mutex;
+
+/// HCI event codes this transport cares about; every other event is ignored.
+const EVENT_COMMAND_COMPLETE: u8 = 0x0E;
+const EVENT_COMMAND_STATUS: u8 = 0x0F;
+const EVENT_LE_META: u8 = 0x3E;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum HCITransportError {
+    /// The H5 frame's trailing CRC didn't match the computed value.
+    CrcMismatch,
+    /// A SLIP-escaped byte sequence was malformed.
+    FramingError,
+    /// `send` was called for an opcode that already has a response in flight.
+    CommandInFlight,
+    /// The underlying serial link closed while a command was outstanding.
+    LinkClosed,
+    /// The controller's response didn't decode as valid HCI.
+    MalformedEvent,
+}
+impl From<HCIConversionError> for HCITransportError {
+    fn from(_: HCIConversionError) -> Self {
+        HCITransportError::MalformedEvent
+    }
+}
+
+/// Raw parameter bytes for an HCI Command Complete/Command Status event, handed back to the
+/// caller of [`HCIAdapter::send`].
+#[derive(Clone, Debug)]
+pub struct HCIResponse {
+    pub opcode: Opcode,
+    pub status: u8,
+    pub return_parameters: Vec<u8>,
+}
+
+/// An LE Meta event (advertising report, connection complete, ...) that wasn't a response to an
+/// in-flight command, yielded on [`HCIAdapter::le_events`].
+#[derive(Clone, Debug)]
+pub struct LEMetaEvent {
+    pub subevent_code: u8,
+    pub parameters: Vec<u8>,
+}
+
+/// H5 (Three-Wire UART) framing: SLIP-escaped, length-prefixed, CRC-checked frames over an
+/// unreliable serial link.
+mod h5 {
+    use super::HCITransportError;
+    use alloc::vec::Vec;
+
+    const SLIP_DELIMITER: u8 = 0xC0;
+    const SLIP_ESC: u8 = 0xDB;
+    const SLIP_ESC_DELIMITER: u8 = 0xDC;
+    const SLIP_ESC_ESC: u8 = 0xDD;
+
+    fn crc16(data: &[u8]) -> u16 {
+        let mut crc: u16 = 0xFFFF;
+        for &byte in data {
+            crc ^= u16::from(byte) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x1021
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        crc
+    }
+
+    /// Prefixes `payload` with its length, appends a trailing CRC-16, SLIP-escapes the result and
+    /// wraps it in `0xC0` delimiters.
+    pub fn encode(payload: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(payload.len() + 4);
+        framed.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        framed.extend_from_slice(payload);
+        let crc = crc16(&framed);
+        framed.extend_from_slice(&crc.to_le_bytes());
+
+        let mut out = Vec::with_capacity(framed.len() + 2);
+        out.push(SLIP_DELIMITER);
+        for byte in framed {
+            match byte {
+                SLIP_DELIMITER => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_DELIMITER]),
+                SLIP_ESC => out.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+                other => out.push(other),
+            }
+        }
+        out.push(SLIP_DELIMITER);
+        out
+    }
+
+    /// Un-escapes one delimited SLIP frame (without the leading/trailing `0xC0`) and verifies its
+    /// length field and trailing CRC, returning the inner payload.
+    pub fn decode(escaped_frame: &[u8]) -> Result<Vec<u8>, HCITransportError> {
+        let mut framed = Vec::with_capacity(escaped_frame.len());
+        let mut iter = escaped_frame.iter().copied();
+        while let Some(byte) = iter.next() {
+            if byte == SLIP_ESC {
+                match iter.next() {
+                    Some(SLIP_ESC_DELIMITER) => framed.push(SLIP_DELIMITER),
+                    Some(SLIP_ESC_ESC) => framed.push(SLIP_ESC),
+                    _ => return Err(HCITransportError::FramingError),
+                }
+            } else {
+                framed.push(byte);
+            }
+        }
+        if framed.len() < 4 {
+            return Err(HCITransportError::FramingError);
+        }
+        let (header, rest) = framed.split_at(2);
+        let (payload, crc_bytes) = rest.split_at(rest.len() - 2);
+        let length = u16::from_le_bytes([header[0], header[1]]) as usize;
+        if length != payload.len() {
+            return Err(HCITransportError::FramingError);
+        }
+        let received_crc = u16::from_le_bytes([crc_bytes[0], crc_bytes[1]]);
+        if crc16(&framed[..framed.len() - 2]) != received_crc {
+            return Err(HCITransportError::CrcMismatch);
+        }
+        Ok(payload.to_vec())
+    }
+
+    /// Scans a raw byte stream for `0xC0` delimiters and yields each complete frame between them
+    /// (still SLIP-escaped, with the delimiters stripped, ready for [`decode`]) — the actual
+    /// demultiplexing an H5 3-wire link needs, since bytes arrive off the wire with no other
+    /// framing. Bytes fed in before the first delimiter are discarded; a delimiter immediately
+    /// followed by another (an empty frame, as produced back-to-back by [`encode`]) yields
+    /// nothing.
+    #[derive(Default)]
+    pub struct FrameDemux {
+        buffer: Vec<u8>,
+        in_frame: bool,
+    }
+    impl FrameDemux {
+        pub fn new() -> Self {
+            Self::default()
+        }
+        pub fn feed(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+            let mut frames = Vec::new();
+            for &byte in bytes {
+                if byte == SLIP_DELIMITER {
+                    if self.in_frame && !self.buffer.is_empty() {
+                        frames.push(core::mem::take(&mut self.buffer));
+                    }
+                    self.in_frame = true;
+                } else if self.in_frame {
+                    self.buffer.push(byte);
+                }
+            }
+            frames
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn encode_decode_round_trip() {
+            let payload = [0x01, 0x02, 0x03, 0x04];
+            let framed = encode(&payload);
+            assert_eq!(framed.first(), Some(&SLIP_DELIMITER));
+            assert_eq!(framed.last(), Some(&SLIP_DELIMITER));
+
+            let mut demux = FrameDemux::new();
+            let frames = demux.feed(&framed);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(decode(&frames[0]).unwrap(), payload.to_vec());
+        }
+
+        #[test]
+        fn encode_escapes_delimiter_and_esc_bytes_in_payload() {
+            let payload = [SLIP_DELIMITER, SLIP_ESC, 0x00];
+            let framed = encode(&payload);
+
+            let mut demux = FrameDemux::new();
+            let frames = demux.feed(&framed);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(decode(&frames[0]).unwrap(), payload.to_vec());
+        }
+
+        #[test]
+        fn decode_rejects_crc_mismatch() {
+            let mut framed = encode(&[0x01, 0x02]);
+            // Flip every bit of the last CRC byte, which for this payload isn't an escaped byte.
+            let last = framed.len() - 2;
+            framed[last] ^= 0xFF;
+            let mut demux = FrameDemux::new();
+            let frames = demux.feed(&framed);
+            assert_eq!(
+                decode(&frames[0]),
+                Err(HCITransportError::CrcMismatch)
+            );
+        }
+
+        #[test]
+        fn decode_rejects_malformed_escape_sequence() {
+            // SLIP_ESC not followed by one of the two valid escape codes.
+            let malformed = [SLIP_ESC, 0x00];
+            assert_eq!(decode(&malformed), Err(HCITransportError::FramingError));
+        }
+
+        #[test]
+        fn demux_splits_back_to_back_frames_on_the_wire() {
+            let first = encode(&[0xAA]);
+            let second = encode(&[0xBB]);
+            // Back-to-back `encode` output shares a delimiter: `0xC0 F1 0xC0 0xC0 F2 0xC0`.
+            let mut on_the_wire = first;
+            on_the_wire.extend_from_slice(&second);
+
+            let mut demux = FrameDemux::new();
+            let frames = demux.feed(&on_the_wire);
+            assert_eq!(frames.len(), 2);
+            assert_eq!(decode(&frames[0]).unwrap(), alloc::vec![0xAA]);
+            assert_eq!(decode(&frames[1]).unwrap(), alloc::vec![0xBB]);
+        }
+
+        #[test]
+        fn demux_buffers_a_frame_split_across_multiple_feeds() {
+            let framed = encode(&[0x01, 0x02, 0x03]);
+            let (first_half, second_half) = framed.split_at(framed.len() / 2);
+
+            let mut demux = FrameDemux::new();
+            assert!(demux.feed(first_half).is_empty());
+            let frames = demux.feed(second_half);
+            assert_eq!(frames.len(), 1);
+            assert_eq!(decode(&frames[0]).unwrap(), alloc::vec![0x01, 0x02, 0x03]);
+        }
+    }
+}
+
+/// Owns the pending-command table and drives a framed HCI link: `send` serializes and frames a
+/// command, matches its Command Complete/Command Status response back by `Opcode`, and LE Meta
+/// events that aren't responses are yielded separately via [`HCIAdapter::le_events`].
+pub struct HCIAdapter<W> {
+    writer: Mutex<W>,
+    pending: Mutex<btree_map::BTreeMap<Opcode, oneshot::Sender<HCIResponse>>>,
+    le_meta_tx: mpsc::UnboundedSender<LEMetaEvent>,
+    le_meta_rx: Mutex<Option<mpsc::UnboundedReceiver<LEMetaEvent>>>,
+}
+impl<W: futures::io::AsyncWrite + Unpin> HCIAdapter<W> {
+    pub fn new(writer: W) -> Self {
+        let (le_meta_tx, le_meta_rx) = mpsc::unbounded();
+        Self {
+            writer: Mutex::new(writer),
+            pending: Mutex::new(btree_map::BTreeMap::new()),
+            le_meta_tx,
+            le_meta_rx: Mutex::new(Some(le_meta_rx)),
+        }
+    }
+
+    /// Takes the LE Meta event stream. Can only be taken once; subsequent calls return `None`.
+    pub async fn le_events(&self) -> Option<mpsc::UnboundedReceiver<LEMetaEvent>> {
+        self.le_meta_rx.lock().await.take()
+    }
+
+    /// Serializes `cmd` with `parameters`, frames it with H5 SLIP framing, writes it to the link,
+    /// and awaits the matching Command Complete/Command Status event.
+    pub async fn send(
+        &self,
+        cmd: LEControllerOpcode,
+        parameters: &[u8],
+    ) -> Result<HCIResponse, HCITransportError> {
+        use futures::io::AsyncWriteExt;
+
+        let opcode = Opcode::from(cmd);
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut pending = self.pending.lock().await;
+            if pending.contains_key(&opcode) {
+                return Err(HCITransportError::CommandInFlight);
+            }
+            pending.insert(opcode, tx);
+        }
+
+        let mut packet = Vec::with_capacity(4 + parameters.len());
+        packet.push(0x01); // HCI Command packet indicator
+        packet.extend_from_slice(&u16::from(opcode).to_le_bytes());
+        packet.push(parameters.len() as u8);
+        packet.extend_from_slice(parameters);
+
+        let framed = h5::encode(&packet);
+        let write_result = self.writer.lock().await.write_all(&framed).await;
+        if write_result.is_err() {
+            self.pending.lock().await.remove(&opcode);
+            return Err(HCITransportError::LinkClosed);
+        }
+
+        rx.await.map_err(|_| HCITransportError::LinkClosed)
+    }
+
+    /// Reads raw bytes from `reader`, demuxes them into H5 frames on their `0xC0` delimiters
+    /// (see [`h5::FrameDemux`]), and feeds each complete frame through [`HCIAdapter::on_frame`].
+    /// Runs until the link closes or a frame fails to decode.
+    pub async fn run<R: futures::io::AsyncRead + Unpin>(
+        &self,
+        mut reader: R,
+    ) -> Result<(), HCITransportError> {
+        use futures::io::AsyncReadExt;
+
+        let mut demux = h5::FrameDemux::new();
+        let mut buf = [0_u8; 256];
+        loop {
+            let n = reader
+                .read(&mut buf)
+                .await
+                .map_err(|_| HCITransportError::LinkClosed)?;
+            if n == 0 {
+                return Err(HCITransportError::LinkClosed);
+            }
+            for frame in demux.feed(&buf[..n]) {
+                self.on_frame(&frame).await?;
+            }
+        }
+    }
+
+    /// Decodes one already-demuxed H5 frame (SLIP-escaped bytes with the leading/trailing
+    /// `0xC0` delimiters already stripped, as yielded by [`h5::FrameDemux::feed`]) containing a
+    /// raw HCI event packet: Command Complete/Command Status are routed to the matching
+    /// in-flight [`HCIAdapter::send`], LE Meta events are forwarded on
+    /// [`HCIAdapter::le_events`], and every other event is ignored.
+    pub async fn on_frame(&self, escaped_frame: &[u8]) -> Result<(), HCITransportError> {
+        let event = h5::decode(escaped_frame)?;
+        self.on_event(&event).await
+    }
+
+    async fn on_event(&self, event: &[u8]) -> Result<(), HCITransportError> {
+        if event.len() < 2 {
+            return Err(HCITransportError::MalformedEvent);
+        }
+        let event_code = event[0];
+        let parameters = &event[2..];
+        match event_code {
+            EVENT_COMMAND_COMPLETE => {
+                if parameters.len() < 3 {
+                    return Err(HCITransportError::MalformedEvent);
+                }
+                let opcode_raw = u16::from_le_bytes([parameters[1], parameters[2]]);
+                let opcode = Opcode::try_from(opcode_raw)?;
+                self.complete(
+                    opcode,
+                    HCIResponse {
+                        opcode,
+                        status: *parameters.get(3).unwrap_or(&0),
+                        return_parameters: parameters[3.min(parameters.len())..].to_vec(),
+                    },
+                )
+                .await;
+            }
+            EVENT_COMMAND_STATUS => {
+                // Status(1) | Num_HCI_Command_Packets(1) | Command_Opcode(2), one byte further in
+                // than Command Complete's Num_HCI_Command_Packets(1) | Command_Opcode(2).
+                if parameters.len() < 4 {
+                    return Err(HCITransportError::MalformedEvent);
+                }
+                let status = parameters[0];
+                let opcode_raw = u16::from_le_bytes([parameters[2], parameters[3]]);
+                let opcode = Opcode::try_from(opcode_raw)?;
+                self.complete(
+                    opcode,
+                    HCIResponse {
+                        opcode,
+                        status,
+                        return_parameters: Vec::new(),
+                    },
+                )
+                .await;
+            }
+            EVENT_LE_META => {
+                if parameters.is_empty() {
+                    return Err(HCITransportError::MalformedEvent);
+                }
+                let _ = self.le_meta_tx.unbounded_send(LEMetaEvent {
+                    subevent_code: parameters[0],
+                    parameters: parameters[1..].to_vec(),
+                });
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn complete(&self, opcode: Opcode, response: HCIResponse) {
+        if let Some(tx) = self.pending.lock().await.remove(&opcode) {
+            let _ = tx.send(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn command_complete_routes_to_the_matching_pending_request() {
+        let adapter = HCIAdapter::new(Cursor::new(Vec::<u8>::new()));
+        let opcode = Opcode::from(LEControllerOpcode::Rand);
+        let (tx, rx) = oneshot::channel();
+        block_on(adapter.pending.lock()).insert(opcode, tx);
+
+        let opcode_bytes = u16::from(opcode).to_le_bytes();
+        let event = [
+            EVENT_COMMAND_COMPLETE,
+            0x00, // Connection_Handle-like byte skipped by `on_event` (event[0..2])
+            1,    // Num_HCI_Command_Packets
+            opcode_bytes[0],
+            opcode_bytes[1],
+            0x00, // Status
+        ];
+        block_on(adapter.on_event(&event)).expect("valid Command Complete event");
+
+        let response = block_on(rx).expect("pending request was resolved");
+        assert_eq!(response.opcode, opcode);
+        assert_eq!(response.status, 0x00);
+    }
+
+    #[test]
+    fn command_status_routes_to_the_matching_pending_request() {
+        let adapter = HCIAdapter::new(Cursor::new(Vec::<u8>::new()));
+        let opcode = Opcode::from(LEControllerOpcode::CreateConnection);
+        let (tx, rx) = oneshot::channel();
+        block_on(adapter.pending.lock()).insert(opcode, tx);
+
+        let opcode_bytes = u16::from(opcode).to_le_bytes();
+        let event = [
+            EVENT_COMMAND_STATUS,
+            0x00, // byte skipped by `on_event` (event[0..2])
+            0x00, // Status
+            1,    // Num_HCI_Command_Packets
+            opcode_bytes[0],
+            opcode_bytes[1],
+        ];
+        block_on(adapter.on_event(&event)).expect("valid Command Status event");
+
+        let response = block_on(rx).expect("pending request was resolved");
+        assert_eq!(response.opcode, opcode);
+        assert_eq!(response.status, 0x00);
+    }
+
+    #[test]
+    fn run_demuxes_frames_from_the_read_side_and_resolves_pending_requests() {
+        let adapter = HCIAdapter::new(Cursor::new(Vec::<u8>::new()));
+        let opcode = Opcode::from(LEControllerOpcode::Rand);
+        let (tx, rx) = oneshot::channel();
+        block_on(adapter.pending.lock()).insert(opcode, tx);
+
+        let opcode_bytes = u16::from(opcode).to_le_bytes();
+        let event = [EVENT_COMMAND_COMPLETE, 0x00, 1, opcode_bytes[0], opcode_bytes[1], 0x00];
+        let on_the_wire = h5::encode(&event);
+
+        // The cursor runs dry after the one frame, so `run` ends with `LinkClosed`.
+        assert_eq!(
+            block_on(adapter.run(Cursor::new(on_the_wire))),
+            Err(HCITransportError::LinkClosed)
+        );
+        let response = block_on(rx).expect("pending request was resolved");
+        assert_eq!(response.opcode, opcode);
+    }
+}
@@ -0,0 +1,188 @@
+//! Pluggable cryptography backend.
+//!
+//! Everything this crate needs from a crypto library is the fixed set of primitives below:
+//! AES-128-ECB (`e`), AES-CMAC, AES-CCM with a configurable MIC length, and the mesh-specific
+//! derived functions `k1`/`k2`/`k3`/`k4`/`s1`. [`MeshCrypto`] is that seam, and [`Crypto`] is
+//! whichever implementation the `crypto-rustcrypto`/`crypto-ring`/`crypto-mbedtls` Cargo feature
+//! selects, so hosts that want hardware acceleration or a FIPS-validated library aren't stuck
+//! with the pure-Rust `no_std` default.
+//!
+//! PARTIAL COMPLETION: the original request asked for three backends (RustCrypto/`ring`/
+//! `mbedtls`). Only `crypto-rustcrypto` is implemented here; `crypto-ring` and `crypto-mbedtls`
+//! are NOT delivered, not just deferred behind a `#[cfg]` — selecting either currently fails the
+//! build with a `compile_error!` (see the bottom of this file) rather than pointing at backend
+//! modules that don't exist. Shipping those two backends for real is follow-up work.
+use crate::crypto::key::{EncryptionKey, PrivacyKey};
+use crate::crypto::{NetworkID, AID};
+use crate::mesh::NID;
+
+/// The primitives the Bluetooth Mesh profile needs from a crypto backend.
+pub trait MeshCrypto {
+    /// AES-128-ECB single block encryption, `e(key, plaintext)` in the spec's notation.
+    fn e(key: &[u8; 16], plaintext: &[u8; 16]) -> [u8; 16];
+
+    /// AES-CMAC over `message`, keyed by `key`.
+    fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16];
+
+    /// AES-CCM authenticated encryption. `mic_size` is the MIC length in bytes (4 or 8 for the
+    /// Mesh profile). Returns the ciphertext followed by the MIC.
+    fn aes_ccm_encrypt(
+        key: &[u8; 16],
+        nonce: &[u8; 13],
+        data: &[u8],
+        additional_data: &[u8],
+        mic_size: usize,
+    ) -> alloc::vec::Vec<u8>;
+
+    /// AES-CCM authenticated decryption, inverse of [`MeshCrypto::aes_ccm_encrypt`]. Returns
+    /// `None` if the MIC doesn't verify.
+    fn aes_ccm_decrypt(
+        key: &[u8; 16],
+        nonce: &[u8; 13],
+        data: &[u8],
+        additional_data: &[u8],
+        mic_size: usize,
+    ) -> Option<alloc::vec::Vec<u8>>;
+
+    /// The `s1` salt generation function.
+    fn s1(m: &[u8]) -> [u8; 16];
+
+    /// The `k1` derivation function.
+    fn k1(n: &[u8], salt: &[u8; 16], p: &[u8]) -> [u8; 16];
+
+    /// The `k2` network key derivation function, producing (NID, `EncryptionKey`, `PrivacyKey`).
+    fn k2(n: &[u8; 16], p: &[u8]) -> (NID, EncryptionKey, PrivacyKey);
+
+    /// The `k3` function, deriving the 64-bit `NetworkID`.
+    fn k3(n: &[u8; 16]) -> NetworkID;
+
+    /// The `k4` function, deriving the 6-bit `AID`.
+    fn k4(n: &[u8; 16]) -> AID;
+}
+
+#[cfg(feature = "crypto-rustcrypto")]
+mod rustcrypto_backend {
+    use super::MeshCrypto;
+    use crate::crypto::key::{EncryptionKey, PrivacyKey};
+    use crate::crypto::{NetworkID, AID};
+    use crate::mesh::NID;
+
+    /// Pure-Rust backend built on the `RustCrypto` crates (`aes`, `cmac`, `ccm`). `no_std`
+    /// compatible; this is the default backend.
+    pub struct RustCryptoBackend;
+    impl MeshCrypto for RustCryptoBackend {
+        fn e(key: &[u8; 16], plaintext: &[u8; 16]) -> [u8; 16] {
+            crate::crypto::aes_cipher::encrypt(key, plaintext)
+        }
+        fn aes_cmac(key: &[u8; 16], message: &[u8]) -> [u8; 16] {
+            crate::crypto::aes_cmac::cmac(key, message)
+        }
+        fn aes_ccm_encrypt(
+            key: &[u8; 16],
+            nonce: &[u8; 13],
+            data: &[u8],
+            additional_data: &[u8],
+            mic_size: usize,
+        ) -> alloc::vec::Vec<u8> {
+            crate::crypto::aes_ccm::encrypt(key, nonce, data, additional_data, mic_size)
+        }
+        fn aes_ccm_decrypt(
+            key: &[u8; 16],
+            nonce: &[u8; 13],
+            data: &[u8],
+            additional_data: &[u8],
+            mic_size: usize,
+        ) -> Option<alloc::vec::Vec<u8>> {
+            crate::crypto::aes_ccm::decrypt(key, nonce, data, additional_data, mic_size)
+        }
+        fn s1(m: &[u8]) -> [u8; 16] {
+            crate::crypto::s1_inner(m)
+        }
+        fn k1(n: &[u8], salt: &[u8; 16], p: &[u8]) -> [u8; 16] {
+            crate::crypto::k1_inner(n, salt, p)
+        }
+        fn k2(n: &[u8; 16], p: &[u8]) -> (NID, EncryptionKey, PrivacyKey) {
+            crate::crypto::k2_inner(n, p)
+        }
+        fn k3(n: &[u8; 16]) -> NetworkID {
+            crate::crypto::k3_inner(n)
+        }
+        fn k4(n: &[u8; 16]) -> AID {
+            crate::crypto::k4_inner(n)
+        }
+    }
+}
+
+#[cfg(feature = "crypto-rustcrypto")]
+pub use rustcrypto_backend::RustCryptoBackend as Crypto;
+
+#[cfg(not(any(
+    feature = "crypto-rustcrypto",
+    feature = "crypto-ring",
+    feature = "crypto-mbedtls"
+)))]
+compile_error!(
+    "one of the `crypto-rustcrypto`, `crypto-ring`, or `crypto-mbedtls` features must be enabled"
+);
+
+#[cfg(all(feature = "crypto-ring", not(feature = "crypto-rustcrypto")))]
+compile_error!(
+    "the `crypto-ring` backend isn't implemented yet (tracked as a follow-up) \u{2014} enable `crypto-rustcrypto` instead"
+);
+
+#[cfg(all(
+    feature = "crypto-mbedtls",
+    not(any(feature = "crypto-rustcrypto", feature = "crypto-ring"))
+))]
+compile_error!(
+    "the `crypto-mbedtls` backend isn't implemented yet (tracked as a follow-up) \u{2014} enable `crypto-rustcrypto` instead"
+);
+
+#[cfg(all(test, feature = "crypto-rustcrypto"))]
+mod tests {
+    use super::{Crypto, MeshCrypto};
+
+    // Bluetooth Mesh Profile sample data (Mesh Profile v1.0, §8.1 "Network Keys").
+    const NET_KEY: [u8; 16] = [
+        0x7d, 0xd7, 0x36, 0x4c, 0xd8, 0x42, 0xad, 0x18, 0xc1, 0x7c, 0x2b, 0x82, 0x0c, 0x84, 0xc3,
+        0xd6,
+    ];
+    const EXPECTED_NID: u8 = 0x7f;
+    const EXPECTED_ENCRYPTION_KEY: [u8; 16] = [
+        0x09, 0x95, 0x3b, 0x1a, 0xd0, 0xab, 0xc7, 0x3d, 0xec, 0x9b, 0x3a, 0x15, 0xc5, 0xda, 0x06,
+        0x52,
+    ];
+    const EXPECTED_PRIVACY_KEY: [u8; 16] = [
+        0x8b, 0x84, 0xee, 0xde, 0xc1, 0x00, 0x06, 0x7d, 0x67, 0x09, 0x71, 0xdd, 0x2a, 0xa7, 0x00,
+        0xcf,
+    ];
+    const EXPECTED_NETWORK_ID: [u8; 8] = [0xff, 0x04, 0x69, 0x58, 0x23, 0x3d, 0xb0, 0x14];
+
+    // Bluetooth Mesh Profile sample data (Mesh Profile v1.0, §8.2 "Application Keys"). `k4` is
+    // defined over an AppKey, not a NetKey, so this uses a separate sample key from `k2`/`k3`.
+    const APP_KEY: [u8; 16] = [
+        0x63, 0x96, 0x47, 0x71, 0x73, 0x4f, 0xbd, 0x76, 0xe3, 0xb4, 0x05, 0x19, 0xd1, 0xd9, 0x4a,
+        0x48,
+    ];
+    const EXPECTED_AID: u8 = 0x38;
+
+    #[test]
+    fn k2_matches_spec_test_vector() {
+        let (nid, encryption_key, privacy_key) = Crypto::k2(&NET_KEY, b"\x00");
+        assert_eq!(u8::from(nid), EXPECTED_NID);
+        assert_eq!(encryption_key.key(), &EXPECTED_ENCRYPTION_KEY);
+        assert_eq!(privacy_key.key(), &EXPECTED_PRIVACY_KEY);
+    }
+
+    #[test]
+    fn k3_matches_spec_test_vector() {
+        let network_id = Crypto::k3(&NET_KEY);
+        assert_eq!(<[u8; 8]>::from(network_id), EXPECTED_NETWORK_ID);
+    }
+
+    #[test]
+    fn k4_matches_spec_test_vector() {
+        let aid = Crypto::k4(&APP_KEY);
+        assert_eq!(u8::from(aid), EXPECTED_AID);
+    }
+}
@@ -0,0 +1,282 @@
+//! Key Refresh Procedure driver: advances a `NetKeyIndex`'s `KeyPhase` through the Normal ->
+//! Phase1 -> Phase2 -> Normal cycle and reacts to the Key Refresh flag of inbound Secure Network
+//! Beacons.
+use crate::beacon::SecureNetworkBeacon;
+use crate::crypto::key::NetKey;
+use crate::crypto::materials::{KeyPair, KeyPhase, NetKeyMap, NetworkSecurityMaterials};
+use crate::mesh::{IVIndex, IVUpdateFlag, NetKeyIndex};
+use core::fmt::{Display, Error, Formatter};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum KeyRefreshError {
+    /// No `NetKey` is installed at the given `NetKeyIndex`.
+    UnknownNetKeyIndex,
+    /// The requested transition doesn't follow the current `KeyPhase`
+    /// (e.g. starting a refresh while already in `Phase1`/`Phase2`, or finishing one from `Normal`).
+    OutOfOrder,
+}
+impl Display for KeyRefreshError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            KeyRefreshError::UnknownNetKeyIndex => write!(f, "no NetKey at that NetKeyIndex"),
+            KeyRefreshError::OutOfOrder => write!(f, "key refresh transition out of order"),
+        }
+    }
+}
+
+/// Drives the Key Refresh Procedure for a single [`NetKeyMap`], transitioning entries through
+/// their [`KeyPhase`] and building the Secure Network Beacon that announces each phase change.
+pub struct KeyRefreshManager<'a> {
+    net_key_map: &'a mut NetKeyMap,
+}
+impl<'a> KeyRefreshManager<'a> {
+    pub fn new(net_key_map: &'a mut NetKeyMap) -> Self {
+        Self { net_key_map }
+    }
+
+    /// Installs `new_key` at `index`, promoting its entry from `KeyPhase::Normal` to
+    /// `Phase1(KeyPair{old, new})`. Both keys are valid for reception; transmission still uses
+    /// `old`.
+    pub fn start_key_refresh(
+        &mut self,
+        index: NetKeyIndex,
+        new_key: &NetKey,
+    ) -> Result<(), KeyRefreshError> {
+        let phase = self
+            .net_key_map
+            .get_keys_mut(index)
+            .ok_or(KeyRefreshError::UnknownNetKeyIndex)?;
+        match phase {
+            KeyPhase::Normal(old) => {
+                *phase = KeyPhase::Phase1(KeyPair {
+                    old: *old,
+                    new: new_key.into(),
+                });
+                Ok(())
+            }
+            KeyPhase::Phase1(_) | KeyPhase::Phase2(_) => Err(KeyRefreshError::OutOfOrder),
+        }
+    }
+
+    /// `Phase1 -> Phase2`: switches the transmit key from `old` to `new`. Both keys remain valid
+    /// for reception during the overlap.
+    pub fn transition_to_phase2(&mut self, index: NetKeyIndex) -> Result<(), KeyRefreshError> {
+        let phase = self
+            .net_key_map
+            .get_keys_mut(index)
+            .ok_or(KeyRefreshError::UnknownNetKeyIndex)?;
+        match phase {
+            KeyPhase::Phase1(pair) => {
+                *phase = KeyPhase::Phase2(*pair);
+                Ok(())
+            }
+            KeyPhase::Normal(_) | KeyPhase::Phase2(_) => Err(KeyRefreshError::OutOfOrder),
+        }
+    }
+
+    /// `Phase2 -> Normal`: discards `old` and collapses the entry to `Normal(new)`, finishing the
+    /// procedure.
+    pub fn finish_key_refresh(&mut self, index: NetKeyIndex) -> Result<(), KeyRefreshError> {
+        let phase = self
+            .net_key_map
+            .get_keys_mut(index)
+            .ok_or(KeyRefreshError::UnknownNetKeyIndex)?;
+        match phase {
+            KeyPhase::Phase2(pair) => {
+                *phase = KeyPhase::Normal(pair.new);
+                Ok(())
+            }
+            KeyPhase::Normal(_) | KeyPhase::Phase1(_) => Err(KeyRefreshError::OutOfOrder),
+        }
+    }
+
+    /// Reacts to an inbound Secure Network Beacon for `index`: a set Key Refresh flag means the
+    /// remote peer has moved to Phase 2, so a local `Phase1` entry follows it there. A clear flag
+    /// while locally in `Phase2` finishes the procedure, mirroring a peer that has already
+    /// collapsed back to `Normal`. Already-`Normal`/already-matching entries are left alone.
+    pub fn handle_secure_beacon(
+        &mut self,
+        index: NetKeyIndex,
+        beacon: &SecureNetworkBeacon,
+    ) -> Result<(), KeyRefreshError> {
+        let phase = self
+            .net_key_map
+            .get_keys(index)
+            .ok_or(KeyRefreshError::UnknownNetKeyIndex)?
+            .phase();
+        match (phase, beacon.key_refresh_flag()) {
+            (crate::crypto::KeyRefreshPhases::First, true) => self.transition_to_phase2(index),
+            (crate::crypto::KeyRefreshPhases::Second, false) => self.finish_key_refresh(index),
+            _ => Ok(()),
+        }
+    }
+
+    /// Builds the Secure Network Beacon announcing `index`'s current phase, with the Key Refresh
+    /// flag set while in `Phase2` and the IV Update flag taken from `iv_update_flag`.
+    pub fn emit_secure_beacon(
+        &self,
+        index: NetKeyIndex,
+        iv_index: IVIndex,
+        iv_update_flag: IVUpdateFlag,
+    ) -> Result<SecureNetworkBeacon, KeyRefreshError> {
+        let phase = self
+            .net_key_map
+            .get_keys(index)
+            .ok_or(KeyRefreshError::UnknownNetKeyIndex)?;
+        let materials: &NetworkSecurityMaterials = phase.tx_key();
+        let key_refresh = matches!(phase.phase(), crate::crypto::KeyRefreshPhases::Second);
+        Ok(SecureNetworkBeacon::new(
+            key_refresh,
+            iv_update_flag,
+            materials.network_id(),
+            iv_index,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::IVIndex;
+
+    fn net_key(byte: u8) -> NetKey {
+        NetKey::new([byte; 16])
+    }
+    fn index() -> NetKeyIndex {
+        NetKeyIndex::from(0_u16)
+    }
+
+    fn map_with_one_normal_key() -> NetKeyMap {
+        let mut map = NetKeyMap::new();
+        map.insert(index(), &net_key(0x11));
+        map
+    }
+
+    #[test]
+    fn start_key_refresh_moves_normal_to_phase1() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+
+        assert_eq!(manager.start_key_refresh(index(), &net_key(0x22)), Ok(()));
+        assert_eq!(
+            map.get_keys(index()).unwrap().phase(),
+            crate::crypto::KeyRefreshPhases::First
+        );
+    }
+
+    #[test]
+    fn start_key_refresh_rejects_unknown_index() {
+        let mut map = NetKeyMap::new();
+        let mut manager = KeyRefreshManager::new(&mut map);
+
+        assert_eq!(
+            manager.start_key_refresh(index(), &net_key(0x22)),
+            Err(KeyRefreshError::UnknownNetKeyIndex)
+        );
+    }
+
+    #[test]
+    fn start_key_refresh_rejects_when_already_in_progress() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+        manager
+            .start_key_refresh(index(), &net_key(0x22))
+            .expect("first refresh starts cleanly");
+
+        assert_eq!(
+            manager.start_key_refresh(index(), &net_key(0x33)),
+            Err(KeyRefreshError::OutOfOrder)
+        );
+    }
+
+    #[test]
+    fn full_phase_cycle_returns_to_normal_with_the_new_key() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+
+        manager
+            .start_key_refresh(index(), &net_key(0x22))
+            .expect("Normal -> Phase1");
+        assert_eq!(manager.transition_to_phase2(index()), Ok(()));
+        assert_eq!(manager.finish_key_refresh(index()), Ok(()));
+        assert_eq!(
+            map.get_keys(index()).unwrap().phase(),
+            crate::crypto::KeyRefreshPhases::Normal
+        );
+    }
+
+    #[test]
+    fn transition_to_phase2_rejects_out_of_order() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+
+        // Still Normal: no Phase1 to advance from.
+        assert_eq!(
+            manager.transition_to_phase2(index()),
+            Err(KeyRefreshError::OutOfOrder)
+        );
+    }
+
+    #[test]
+    fn finish_key_refresh_rejects_out_of_order() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+        manager
+            .start_key_refresh(index(), &net_key(0x22))
+            .expect("Normal -> Phase1");
+
+        // Still Phase1: finishing jumps straight past Phase2.
+        assert_eq!(
+            manager.finish_key_refresh(index()),
+            Err(KeyRefreshError::OutOfOrder)
+        );
+    }
+
+    #[test]
+    fn handle_secure_beacon_follows_peer_into_phase2() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+        manager
+            .start_key_refresh(index(), &net_key(0x22))
+            .expect("Normal -> Phase1");
+
+        let beacon = SecureNetworkBeacon::new(true, IVUpdateFlag::Normal, 0u64.into(), IVIndex::from(0));
+        assert_eq!(manager.handle_secure_beacon(index(), &beacon), Ok(()));
+        assert_eq!(
+            map.get_keys(index()).unwrap().phase(),
+            crate::crypto::KeyRefreshPhases::Second
+        );
+    }
+
+    #[test]
+    fn handle_secure_beacon_finishes_when_peer_drops_the_flag_in_phase2() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+        manager
+            .start_key_refresh(index(), &net_key(0x22))
+            .expect("Normal -> Phase1");
+        manager
+            .transition_to_phase2(index())
+            .expect("Phase1 -> Phase2");
+
+        let beacon = SecureNetworkBeacon::new(false, IVUpdateFlag::Normal, 0u64.into(), IVIndex::from(0));
+        assert_eq!(manager.handle_secure_beacon(index(), &beacon), Ok(()));
+        assert_eq!(
+            map.get_keys(index()).unwrap().phase(),
+            crate::crypto::KeyRefreshPhases::Normal
+        );
+    }
+
+    #[test]
+    fn handle_secure_beacon_is_a_no_op_when_already_in_sync() {
+        let mut map = map_with_one_normal_key();
+        let mut manager = KeyRefreshManager::new(&mut map);
+
+        let beacon = SecureNetworkBeacon::new(false, IVUpdateFlag::Normal, 0u64.into(), IVIndex::from(0));
+        assert_eq!(manager.handle_secure_beacon(index(), &beacon), Ok(()));
+        assert_eq!(
+            map.get_keys(index()).unwrap().phase(),
+            crate::crypto::KeyRefreshPhases::Normal
+        );
+    }
+}
@@ -1,9 +1,11 @@
 //! Collection of security materials (Keys, NID, AID, etc) used for encryption and decryption.
+use crate::crypto::backend::{Crypto, MeshCrypto};
 use crate::crypto::key::{
     AppKey, BeaconKey, DevKey, EncryptionKey, IdentityKey, NetKey, PrivacyKey,
 };
-use crate::crypto::{k2, KeyRefreshPhases, NetworkID, AID};
-use crate::mesh::{AppKeyIndex, IVIndex, IVUpdateFlag, NetKeyIndex, NID};
+use crate::crypto::replay_protection::{ReplayError, ReplayProtection};
+use crate::crypto::{KeyRefreshPhases, NetworkID, AID};
+use crate::mesh::{AppKeyIndex, IVIndex, IVUpdateFlag, NetKeyIndex, UnicastAddress, NID, SEQ};
 use alloc::collections::btree_map;
 use core::fmt::{Display, Error, Formatter};
 
@@ -45,7 +47,7 @@ impl NetworkKeys {
 }
 impl From<&NetKey> for NetworkKeys {
     fn from(k: &NetKey) -> Self {
-        let (nid, encryption, privacy) = k2(k.key(), b"\x00");
+        let (nid, encryption, privacy) = Crypto::k2(k.key(), b"\x00");
         Self::new(nid, encryption, privacy)
     }
 }
@@ -91,12 +93,17 @@ impl NetworkSecurityMaterials {
 impl NetworkSecurityMaterials {}
 impl From<&NetKey> for NetworkSecurityMaterials {
     fn from(k: &NetKey) -> Self {
+        // `network_id`/`identity_key`/`beacon_key` are derived here (rather than through
+        // `key.rs`'s own `From<&NetKey>` impls) so that every derived key, not just the `k2` ones
+        // in `network_keys`, goes through the `Crypto` backend.
+        let identity_key = Crypto::k1(k.key(), &Crypto::s1(b"nkik"), b"id128\x01").into();
+        let beacon_key = Crypto::k1(k.key(), &Crypto::s1(b"nkbk"), b"id128\x01").into();
         Self {
             net_key: *k,
             network_keys: k.into(),
-            network_id: k.into(),
-            identity_key: k.into(),
-            beacon_key: k.into(),
+            network_id: Crypto::k3(k.key()),
+            identity_key,
+            beacon_key,
         }
     }
 }
@@ -299,4 +306,19 @@ pub struct SecurityMaterials {
     pub dev_key: DevKey,
     pub net_key_map: NetKeyMap,
     pub app_key_map: AppKeyMap,
+    #[cfg_attr(feature = "serde-1", serde(skip))]
+    pub replay_protection: ReplayProtection,
+}
+impl SecurityMaterials {
+    /// Checks `seq` from `src` under `iv_index` against the replay protection window, rejecting
+    /// a decrypted Network PDU before it would otherwise be delivered to the transport layer.
+    /// See [`ReplayProtection::check_and_update`].
+    pub fn check_replay(
+        &mut self,
+        src: UnicastAddress,
+        iv_index: IVIndex,
+        seq: SEQ,
+    ) -> Result<(), ReplayError> {
+        self.replay_protection.check_and_update(src, iv_index, seq)
+    }
 }
@@ -1,42 +1,305 @@
 //! Network Input/Output Interface and Filter.
-/*
+//!
+//! This is the seam between the network layer and concrete bearers (the advertising bearer, the
+//! GATT proxy bearer, ...): bearers implement [`InputInterface`] to push PDUs they've received
+//! into a shared [`InterfaceSink`], and the network layer uses [`OutputInterfaces`] to fan an
+//! outgoing PDU out to every registered [`OutputInterface`].
+use crate::crypto::materials::SecurityMaterials;
+use crate::crypto::replay_protection::ReplayError;
+use crate::mesh::{SequenceNumber, TTL};
+use crate::net::{BearerError, IncomingEncryptedNetworkPDU, OutgoingEncryptedNetworkPDU};
+use alloc::vec::Vec;
+use core::fmt::{Display, Error, Formatter};
+
+/// Identifies one registered bearer so the relay policy can avoid echoing a PDU back onto the
+/// interface it arrived on.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct InterfaceIndex(pub u16);
+
+/// Consumes PDUs pushed in by [`InputInterface`] implementations.
 pub trait InterfaceSink {
-    fn consume_pdu(&mut self, pdu: &IncomingEncryptedNetworkPDU);
+    fn consume_pdu(&mut self, from: InterfaceIndex, pdu: &IncomingEncryptedNetworkPDU);
 }
+
+/// Implemented by a concrete bearer (advertising bearer, GATT proxy bearer, ...) so it can push
+/// PDUs it receives into the shared sink.
 pub trait InputInterface<Sink: InterfaceSink> {
-    fn take_sink(&mut self, sink: Sink);
+    fn take_sink(&mut self, index: InterfaceIndex, sink: Sink);
 }
 
+/// Owns the shared [`InterfaceSink`] and hands it out to every registered [`InputInterface`].
 pub struct InputInterfaces<Sink: InterfaceSink + Clone> {
     sink: Sink,
+    next_index: u16,
 }
 impl<Sink: InterfaceSink + Clone> InputInterfaces<Sink> {
     pub fn new(sink: Sink) -> Self {
-        Self { sink }
+        Self {
+            sink,
+            next_index: 0,
+        }
     }
-    pub fn add(&self, interface: &mut dyn InputInterface<Sink>) {
-        interface.take_sink(self.sink.clone())
+    pub fn add(&mut self, interface: &mut dyn InputInterface<Sink>) -> InterfaceIndex {
+        let index = InterfaceIndex(self.next_index);
+        self.next_index += 1;
+        interface.take_sink(index, self.sink.clone());
+        index
     }
 }
+
+/// Implemented by a concrete bearer so the network layer can send a PDU out over it.
 pub trait OutputInterface {
     fn send_pdu(&mut self, pdu: &OutgoingEncryptedNetworkPDU) -> Result<(), BearerError>;
 }
+
+/// Per-interface relay policy: whether a PDU that came in on `from` is allowed to be relayed back
+/// out `to`, and the TTL it should be relayed with. The default policy decrements TTL by one and
+/// denies relaying a PDU back onto the interface it arrived on.
+pub trait RelayPolicy {
+    fn allow_relay(&self, from: InterfaceIndex, to: InterfaceIndex, ttl: TTL) -> Option<TTL>;
+}
+
+/// Never relays a PDU back onto the interface it arrived on; otherwise decrements TTL by one and
+/// allows the relay as long as the result is still at least `1`.
+pub struct DefaultRelayPolicy;
+impl RelayPolicy for DefaultRelayPolicy {
+    fn allow_relay(&self, from: InterfaceIndex, to: InterfaceIndex, ttl: TTL) -> Option<TTL> {
+        if from == to {
+            return None;
+        }
+        ttl.decrement()
+    }
+}
+
+/// Deduplicates PDUs already relayed, keyed by source address and `SEQ`, so a PDU seen from
+/// multiple paths is only relayed once.
+pub struct MessageCache {
+    seen: Vec<(crate::mesh::UnicastAddress, SequenceNumber)>,
+    capacity: usize,
+}
+impl MessageCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            seen: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+    /// Returns `true` the first time `(src, seq)` is seen, `false` on every subsequent call. A
+    /// `capacity` of `0` disables the cache: dedup is skipped and every call returns `true`.
+    pub fn insert_if_new(&mut self, src: crate::mesh::UnicastAddress, seq: SequenceNumber) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        if self.seen.iter().any(|&(s, n)| s == src && n == seq) {
+            return false;
+        }
+        if self.seen.len() >= self.capacity {
+            self.seen.remove(0);
+        }
+        self.seen.push((src, seq));
+        true
+    }
+}
+
+/// Fans a single outgoing PDU out to every registered [`OutputInterface`]. [`relay_pdu`]
+/// applies the relay policy so a PDU isn't echoed back onto the interface it arrived on;
+/// message-cache dedup is not done here but by [`NetworkRelay`] through its [`MessageCache`].
+///
+/// [`relay_pdu`]: OutputInterfaces::relay_pdu
 #[derive(Default)]
 pub struct OutputInterfaces<'a> {
-    interfaces: Vec<&'a mut dyn OutputInterface>,
+    interfaces: Vec<(InterfaceIndex, &'a mut dyn OutputInterface)>,
+    relay_policy: Option<&'a dyn RelayPolicy>,
 }
 impl<'a> OutputInterfaces<'a> {
     pub fn new() -> Self {
-        Self::default()
+        Self {
+            interfaces: Vec::new(),
+            relay_policy: None,
+        }
     }
-    pub fn add_interface<'b: 'a>(&mut self, interface: &'b mut dyn OutputInterface) {
-        self.interfaces.push(interface)
+    pub fn with_relay_policy(relay_policy: &'a dyn RelayPolicy) -> Self {
+        Self {
+            interfaces: Vec::new(),
+            relay_policy: Some(relay_policy),
+        }
+    }
+    pub fn add_interface<'b: 'a>(
+        &mut self,
+        index: InterfaceIndex,
+        interface: &'b mut dyn OutputInterface,
+    ) {
+        self.interfaces.push((index, interface))
     }
+    /// Sends `pdu` out every registered interface, collecting the first [`BearerError`] hit (if
+    /// any) while still attempting delivery on the rest.
     pub fn send_pdu(&mut self, pdu: &OutgoingEncryptedNetworkPDU) -> Result<(), BearerError> {
-        for interface in self.interfaces.iter_mut() {
-            (*interface).send_pdu(pdu)?
+        let mut result = Ok(());
+        for (_, interface) in self.interfaces.iter_mut() {
+            if let Err(e) = interface.send_pdu(pdu) {
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+    /// Relays a PDU received on `from` out every other registered interface whose
+    /// [`RelayPolicy`] allows it, adjusting TTL per interface.
+    pub fn relay_pdu(
+        &mut self,
+        from: InterfaceIndex,
+        pdu: &OutgoingEncryptedNetworkPDU,
+        ttl: TTL,
+    ) -> Result<(), BearerError> {
+        let policy = self.relay_policy;
+        let mut result = Ok(());
+        for (index, interface) in self.interfaces.iter_mut() {
+            let allowed_ttl = match policy {
+                Some(policy) => policy.allow_relay(from, *index, ttl),
+                None if *index != from => ttl.decrement(),
+                None => None,
+            };
+            if let Some(relay_ttl) = allowed_ttl {
+                if let Err(e) = interface.send_pdu(&pdu.with_ttl(relay_ttl)) {
+                    result = result.and(Err(e));
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Why a PDU was dropped on the input path, either before or after a successful decryption.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum DecryptError {
+    /// No network in `sm.net_key_map` both matched the PDU's `NID` and successfully decrypted it.
+    NoMatchingNetKey,
+    /// Decryption succeeded but the PDU failed the replay check.
+    Replay(ReplayError),
+}
+impl Display for DecryptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        match self {
+            DecryptError::NoMatchingNetKey => write!(f, "no NetKey matched NID and decrypted PDU"),
+            DecryptError::Replay(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Attempts to decrypt an incoming Network PDU against every `NID`-matching network in
+/// `sm.net_key_map`, dropping the PDU if none match (matches `NetKeyMap::matching_nid`'s
+/// contract: only a successful decryption, not just a `NID` match, confirms the right network),
+/// then runs it through `sm`'s replay protection before accepting it.
+pub fn decrypt_with_matching_nid(
+    sm: &mut SecurityMaterials,
+    pdu: &IncomingEncryptedNetworkPDU,
+) -> Result<crate::net::NetworkPDU, DecryptError> {
+    let decrypted = sm
+        .net_key_map
+        .matching_nid(pdu.nid())
+        .find_map(|(_index, materials)| pdu.decrypt(materials))
+        .ok_or(DecryptError::NoMatchingNetKey)?;
+    sm.check_replay(decrypted.src(), decrypted.iv_index(), decrypted.seq())
+        .map(|()| decrypted)
+        .map_err(DecryptError::Replay)
+}
+
+/// Glues the input path (NID-matching decrypt + replay check) to the output path (relay fan-out
+/// with TTL decrement and message-cache dedup): an [`InterfaceSink`] that decrypts each incoming
+/// PDU against `security_materials`, drops it if it's a replay or fails to decrypt, and otherwise
+/// relays it out every interface in `outputs` other than the one it arrived on, at most once per
+/// `(src, SEQ)` per `cache`.
+pub struct NetworkRelay<'a> {
+    pub security_materials: &'a mut SecurityMaterials,
+    pub outputs: OutputInterfaces<'a>,
+    pub cache: MessageCache,
+}
+impl<'a> InterfaceSink for NetworkRelay<'a> {
+    fn consume_pdu(&mut self, from: InterfaceIndex, pdu: &IncomingEncryptedNetworkPDU) {
+        let decrypted = match decrypt_with_matching_nid(self.security_materials, pdu) {
+            Ok(decrypted) => decrypted,
+            Err(_) => return,
+        };
+        if !self.cache.insert_if_new(decrypted.src(), decrypted.seq()) {
+            return;
+        }
+        let _ = self
+            .outputs
+            .relay_pdu(from, &pdu.to_outgoing(), decrypted.ttl());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct MockBearer {
+        sent: Vec<OutgoingEncryptedNetworkPDU>,
+    }
+    impl MockBearer {
+        fn new() -> Self {
+            Self { sent: Vec::new() }
         }
-        Ok(())
+    }
+    impl OutputInterface for MockBearer {
+        fn send_pdu(&mut self, pdu: &OutgoingEncryptedNetworkPDU) -> Result<(), BearerError> {
+            self.sent.push(pdu.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn relay_goes_out_the_other_interface_exactly_once() {
+        let mut bearer_a = MockBearer::new();
+        let mut bearer_b = MockBearer::new();
+        let index_a = InterfaceIndex(0);
+        let index_b = InterfaceIndex(1);
+
+        let mut outputs = OutputInterfaces::new();
+        outputs.add_interface(index_a, &mut bearer_a);
+        outputs.add_interface(index_b, &mut bearer_b);
+
+        let pdu = OutgoingEncryptedNetworkPDU::new(alloc::vec![0xAA; 16]);
+        let ttl = TTL::new(5).expect("5 is a valid TTL");
+
+        outputs
+            .relay_pdu(index_a, &pdu, ttl)
+            .expect("relay should succeed");
+
+        assert_eq!(
+            bearer_a.sent.len(),
+            0,
+            "must not echo the PDU back onto the interface it arrived on"
+        );
+        assert_eq!(
+            bearer_b.sent.len(),
+            1,
+            "must relay the PDU out the other interface exactly once"
+        );
+    }
+
+    #[test]
+    fn message_cache_dedups_by_src_and_seq() {
+        let mut cache = MessageCache::new(16);
+        let src = crate::mesh::UnicastAddress::from(0x0001);
+        let seq = SequenceNumber::from(1);
+
+        assert!(cache.insert_if_new(src, seq), "first sighting is new");
+        assert!(
+            !cache.insert_if_new(src, seq),
+            "repeat of the same (src, SEQ) must not be new"
+        );
+    }
+
+    #[test]
+    fn zero_capacity_cache_never_panics_and_never_dedups() {
+        let mut cache = MessageCache::new(0);
+        let src = crate::mesh::UnicastAddress::from(0x0001);
+        let seq = SequenceNumber::from(1);
+
+        assert!(cache.insert_if_new(src, seq));
+        assert!(
+            cache.insert_if_new(src, seq),
+            "a disabled cache must not dedup"
+        );
     }
 }
-*/